@@ -37,7 +37,7 @@
         ];
   
         shellHook = ''
-          alias test='cargo run ./test.ir; nasm -felf64 ./build/out.asm; ld -o ./build/test.out ./build/out.o; ./build/test.out'
+          alias test='cargo run ./test.ir -o ./build/out.asm; nasm -felf64 ./build/out.asm; ld -o ./build/test.out ./build/out.o; ./build/test.out'
         '';
 
         RUST_SRC_PATH = "${pkgs.rust.packages.stable.rustPlatform.rustLibSrc}";
@@ -1,6 +1,8 @@
 use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use codegen::CodeGenerator;
 use scanner::LexingError;
@@ -11,57 +13,321 @@ pub mod parser;
 pub mod regalloc;
 pub mod scanner;
 
+const HELP: &str = "\
+Usage: forma [options] [file]
+
+Compiles a Forma IR file to x86-64 assembly. Reads stdin when no file, or
+`-`, is given.
+
+Options:
+    -o <path>       Write the output to <path> (default: out.asm)
+    --emit=<stage>  Print a stage and stop: tokens, ast, ir, regalloc, asm
+                    (asm also writes the output)
+    --check         Run the front-end only, without generating or writing code
+    --time          Print the time spent in each stage to stderr
+    --help          Print this message
+    --version       Print the compiler version
+
+Instructions:
+    define  ret  call  exit  jmp  branch
+    add  sub  mul  div  cmp le
+
+Types:
+    i32
+
+Reserved keywords (scanned, but not compiled yet):
+    switch  nop  trap  mod  udiv  umod  checked
+    and  or  xor  not  shl  shr
+    brcmp  select  phi
+    load  store  alloca  global  addr  strlen  write
+    zext  sext  trunc  to
+    eq  ne  lt  gt  ge  ult  ule  ugt  uge
+    i1  i64  f64
+";
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args = env::args().skip(1);
 
-    let file_path = &args[1];
+    let mut file_path = None;
+    let mut options = Options {
+        output_path: String::from("out.asm"),
+        emit: None,
+        check: false,
+        time: false,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                print!("{HELP}");
+                return;
+            }
+            "--version" | "-V" => {
+                println!("forma {}", env!("CARGO_PKG_VERSION"));
+                return;
+            }
+            "--check" => options.check = true,
+            "--time" => options.time = true,
+            "-o" => match args.next() {
+                Some(path) => options.output_path = path,
+                None => {
+                    println!("Error: Expected an output path after -o");
+                    std::process::exit(1);
+                }
+            },
+            _ if arg.starts_with("--emit=") => match Emit::from_name(&arg["--emit=".len()..]) {
+                Some(emit) => options.emit = Some(emit),
+                None => {
+                    println!("Error: Unknown --emit stage, expected tokens|ast|ir|regalloc|asm");
+                    std::process::exit(1);
+                }
+            },
+            // `-` alone means stdin, anything else dash-prefixed is a mistyped option
+            _ if arg.starts_with('-') && arg != "-" => {
+                println!("Error: Unknown option {arg}");
+                std::process::exit(1);
+            }
+            _ if file_path.is_some() => {
+                println!("Error: Unexpected argument {arg}, only one input file may be given");
+                std::process::exit(1);
+            }
+            _ => file_path = Some(arg),
+        }
+    }
+
+    // --check stops before regalloc, so those stages would never be printed
+    if options.check && matches!(options.emit, Some(Emit::Regalloc | Emit::Asm)) {
+        println!("Error: --check cannot be combined with --emit=regalloc or --emit=asm");
+        std::process::exit(1);
+    }
 
-    run_file(file_path);
+    // Read from stdin when no file, or `-`, is given
+    let result = match file_path.as_deref() {
+        None | Some("-") => run_stdin(&options),
+        Some(path) => run_file(path, &options),
+    };
+
+    if let Err(err) = result {
+        report_error(&err);
+        std::process::exit(1);
+    }
 }
 
-fn run_file(file_path: &String) {
-    let contents = fs::read_to_string(file_path).expect("Unable to read file");
-    run(&contents);
+pub struct Options {
+    pub output_path: String,
+    /// Pipeline stage to print; every stage but `Asm` stops the pipeline after printing
+    pub emit: Option<Emit>,
+    /// Stop after lowering, skipping regalloc, codegen and the output file
+    pub check: bool,
+    /// Report the time spent in each stage on stderr
+    pub time: bool,
 }
 
-fn run(source: &str) {
-    let mut source_scanner = scanner::Scanner::new(source);
-    let tokens = match source_scanner.scan_tokens() {
-        Ok(x) => x,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    Regalloc,
+    Asm,
+}
 
-        Err(LexingError::UnexpectedCharacter { line }) => error(line, "Unexpected Char"),
-        Err(LexingError::UnterminatedString { line }) => error(line, "Unterminated Str"),
-    };
+impl Emit {
+    fn from_name(name: &str) -> Option<Emit> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "ir" => Some(Emit::Ir),
+            "regalloc" => Some(Emit::Regalloc),
+            "asm" => Some(Emit::Asm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Lexing(Vec<LexingError>),
+    Read { path: String, error: io::Error },
+    Write { path: String, error: io::Error },
+}
+
+fn run_file(file_path: &str, options: &Options) -> Result<(), CompileError> {
+    let contents = fs::read_to_string(file_path).map_err(|error| CompileError::Read {
+        path: file_path.to_string(),
+        error,
+    })?;
 
-    //dbg!(&tokens);
+    run(&contents, options)
+}
 
-    let mut parser = parser::Parser::new(tokens);
-    let ast = parser.parse().unwrap();
+fn run_stdin(options: &Options) -> Result<(), CompileError> {
+    let mut contents = String::new();
+    io::stdin()
+        .read_to_string(&mut contents)
+        .map_err(|error| CompileError::Read {
+            path: String::from("stdin"),
+            error,
+        })?;
 
-    dbg!(&ast);
+    run(&contents, options)
+}
+
+fn run(source: &str, options: &Options) -> Result<(), CompileError> {
+    let mut timings = Timings::default();
+    let result = run_stages(source, options, &mut timings);
+
+    if options.time {
+        timings.report();
+    }
+
+    result
+}
 
-    let low_ir = lowering::lower(ast);
+fn run_stages(source: &str, options: &Options, timings: &mut Timings) -> Result<(), CompileError> {
+    let (tokens, errors) = timings.time("scanning", || {
+        let mut source_scanner = scanner::Scanner::new(source);
+        source_scanner.scan_tokens_recovering()
+    });
 
-    dbg!(&low_ir);
+    if !errors.is_empty() {
+        return Err(CompileError::Lexing(errors));
+    }
 
-    let reg = regalloc::allocate_registers(low_ir);
+    if options.emit == Some(Emit::Tokens) {
+        println!("{tokens:#?}");
+        return Ok(());
+    }
 
-    dbg!(&reg);
+    let ast = timings.time("parsing", || {
+        let mut parser = parser::Parser::new(tokens);
+        parser.parse().unwrap()
+    });
 
-    let mut generator = CodeGenerator::new();
-    let code = generator.generate(reg);
+    if options.emit == Some(Emit::Ast) {
+        println!("{ast:#?}");
+        return Ok(());
+    }
 
-    dbg!(&code);
+    let low_ir = timings.time("lowering", || lowering::lower(ast));
+
+    if options.emit == Some(Emit::Ir) {
+        println!("{low_ir:#?}");
+        return Ok(());
+    }
+
+    if options.check {
+        return Ok(());
+    }
+
+    let reg = timings.time("regalloc", || regalloc::allocate_registers(low_ir));
+
+    if options.emit == Some(Emit::Regalloc) {
+        println!("{reg:#?}");
+        return Ok(());
+    }
+
+    let code = timings.time("codegen", || {
+        let mut generator = CodeGenerator::new();
+        generator.generate(reg)
+    });
+
+    if options.emit == Some(Emit::Asm) {
+        println!("{code}");
+    }
+
+    timings
+        .time("writing", || write_output(&options.output_path, &code))
+        .map_err(|error| CompileError::Write {
+            path: options.output_path.clone(),
+            error,
+        })
+}
+
+/// Wall-clock time spent in each pipeline stage, in the order they ran
+#[derive(Default)]
+struct Timings {
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    fn time<T>(&mut self, stage: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+
+        self.stages.push((stage, start.elapsed()));
+
+        result
+    }
+
+    fn report(&self) {
+        for (stage, duration) in &self.stages {
+            eprintln!("{stage:<10} {duration:>12.3?}");
+        }
+
+        let total: Duration = self.stages.iter().map(|(_, duration)| *duration).sum();
+        eprintln!("{:<10} {total:>12.3?}", "total");
+    }
+}
+
+fn write_output(output_path: &str, code: &str) -> io::Result<()> {
+    if let Some(parent) = Path::new(output_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = fs::File::create(output_path)?;
+    file.write_all(code.as_bytes())
+}
+
+fn report_error(err: &CompileError) {
+    match err {
+        CompileError::Lexing(errors) => {
+            for error in errors {
+                report_lexing_error(error);
+            }
+        }
+
+        CompileError::Read { path, error } => println!("Error: Unable to read {path}: {error}"),
+        CompileError::Write { path, error } => println!("Error: Unable to write {path}: {error}"),
+    }
+}
 
-    let mut file = fs::File::create("build/out.asm").unwrap();
-    file.write_all(code.as_bytes()).unwrap();
+fn report_lexing_error(err: &LexingError) {
+    match err {
+        LexingError::UnexpectedCharacter {
+            line,
+            column,
+            character,
+        } => error(*line, *column, &format!("Unexpected character '{character}'")),
+        LexingError::UnterminatedString { line, column } => {
+            error(*line, *column, "Unterminated Str")
+        }
+        LexingError::UnterminatedComment { line, column } => {
+            error(*line, *column, "Unterminated Comment")
+        }
+        LexingError::InvalidIntLiteral { line, column } => {
+            error(*line, *column, "Invalid Int Literal")
+        }
+        LexingError::IntegerOverflow {
+            line,
+            column,
+            literal,
+        } => error(*line, *column, &format!("Int Literal Overflow: {literal}")),
+        LexingError::InvalidEscape { line, column } => {
+            error(*line, *column, "Invalid Escape")
+        }
+        LexingError::InvalidCharLiteral { line, column } => {
+            error(*line, *column, "Invalid Char Literal")
+        }
+    }
 }
 
-fn error(line: i32, message: &str) -> ! {
-    report(line, "", message);
+fn error(line: i32, column: i32, message: &str) {
+    report(line, column, "", message);
 }
 
-fn report(line: i32, position: &str, message: &str) -> ! {
-    println!("[line {line}] Error {position}: {message}");
-    std::process::exit(1);
+fn report(line: i32, column: i32, position: &str, message: &str) {
+    // The scanner counts columns from 0, editors count them from 1
+    let column = column + 1;
+    println!("[line {line}, col {column}] Error {position}: {message}");
 }
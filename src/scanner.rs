@@ -1,10 +1,22 @@
+/// Columns advance to the next multiple of this on a tab
+const TAB_WIDTH: i32 = 4;
+
 #[derive(Debug)]
 pub enum LexingError {
-    UnexpectedCharacter { line: i32 },
-    UnterminatedString { line: i32 },
+    UnexpectedCharacter {
+        line: i32,
+        column: i32,
+        character: char,
+    },
+    UnterminatedString { line: i32, column: i32 },
+    UnterminatedComment { line: i32, column: i32 },
+    InvalidIntLiteral { line: i32, column: i32 },
+    IntegerOverflow { line: i32, column: i32, literal: String },
+    InvalidEscape { line: i32, column: i32 },
+    InvalidCharLiteral { line: i32, column: i32 },
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Single-character tokens
     LeftParen,
@@ -15,31 +27,77 @@ pub enum TokenType {
     Colon,
     Equal,
 
+    // Multi-character tokens
+    Arrow,
+
     // Literals
     Function { name: String },
     Label { name: String },
     Register { name: String },
     String { content: String },
     IntLiteral { value: i64 },
+    FloatLiteral { value: f64 },
 
     // Types
     I32,
+    I64,
+    F64,
+    I1,
 
     // Keywords
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    UDiv,
+    UMod,
+    Checked,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
     Define,
+    Global,
+    Addr,
+    StrLen,
     Return,
     Call,
     Exit,
     Jmp,
     Branch,
+    BrCmp,
     ICmp,
+    Load,
+    Store,
+    Alloca,
+    Phi,
+    Nop,
+    Trap,
+    Zext,
+    Sext,
+    Trunc,
+    Write,
+    Select,
+    Switch,
+    To,
 
     // Compare Types
     LE,
+    LT,
+    GT,
+    GE,
+    EQ,
+    NE,
+    ULT,
+    UGT,
+    ULE,
+    UGE,
+
+    // Placeholder left where a lexing error was recovered from
+    Error,
 
     EOF,
 }
@@ -48,20 +106,73 @@ pub enum TokenType {
 pub struct Token {
     pub token_type: TokenType,
     pub line: i32,
+    pub column: i32,
+    // Byte offsets into the source, `start..end` slices out the token's text
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Default)]
 pub struct Scanner {
     index: usize,
     line: i32,
+    column: i32,
+    // Column where the token currently being scanned begins
+    start_column: i32,
+    // Byte offset of `index` in the source, and of the current token's start
+    offset: usize,
+    start_offset: usize,
     chars: Vec<char>,
 }
 
+/// Scans arbitrary bytes, e.g. from a fuzzer. Invalid UTF-8 is replaced with
+/// U+FFFD, which then reports as an unexpected character, so any input yields
+/// `Ok` or `Err` and never a panic.
+pub fn scan_bytes(data: &[u8]) -> Result<Vec<Token>, LexingError> {
+    Scanner::new(&String::from_utf8_lossy(data)).scan_tokens()
+}
+
+pub struct Tokens<'a> {
+    scanner: &'a mut Scanner,
+    pending: Vec<Token>,
+    done: bool,
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Result<Token, LexingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            if self.done {
+                return None;
+            }
+
+            match self.scanner.scan_token(&mut self.pending) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.scanner.finish(&mut self.pending);
+                    self.done = true;
+                }
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+
+        Some(Ok(self.pending.remove(0)))
+    }
+}
+
 impl Scanner {
     pub fn new(source: &str) -> Scanner {
         let chars: Vec<char> = source.chars().collect();
         Scanner {
-            line: 0,
+            line: 1,
+            column: 0,
+            start_column: 0,
+            offset: 0,
+            start_offset: 0,
             index: 0,
             chars,
         }
@@ -70,27 +181,78 @@ impl Scanner {
     pub fn scan_tokens(&mut self) -> Result<Vec<Token>, LexingError> {
         let mut tokens = Vec::new();
 
-        self.line = 0;
+        self.reset();
 
         while self.scan_token(&mut tokens)? {}
 
+        self.finish(&mut tokens);
+
+        Ok(tokens)
+    }
+
+    /// Lazily scans one token at a time, yielding the same sequence as
+    /// `scan_tokens` and stopping after the first error.
+    pub fn tokens(&mut self) -> Tokens<'_> {
+        self.reset();
+
+        Tokens {
+            scanner: self,
+            pending: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Like `scan_tokens`, but keeps going after an error. Each error leaves a
+    /// `TokenType::Error` in the token stream and scanning resumes right after
+    /// the text that caused it.
+    pub fn scan_tokens_recovering(&mut self) -> (Vec<Token>, Vec<LexingError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+
+        self.reset();
+
+        loop {
+            match self.scan_token(&mut tokens) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) => {
+                    errors.push(error);
+                    self.push_token(&mut tokens, TokenType::Error);
+                }
+            }
+        }
+
+        self.finish(&mut tokens);
+
+        (tokens, errors)
+    }
+
+    fn finish(&self, tokens: &mut Vec<Token>) {
         tokens.push(Token {
             token_type: TokenType::EOF,
             line: self.line,
+            column: self.column,
+            start: self.offset,
+            end: self.offset,
         });
+    }
 
+    // Every entry point starts over from the top, even if an earlier scan
+    // stopped at an error or its iterator was dropped part way through
+    fn reset(&mut self) {
         self.index = 0;
-        self.line = 0;
-
-        Ok(tokens)
+        self.offset = 0;
+        self.line = 1;
+        self.column = 0;
     }
 
     pub fn scan_token(&mut self, tokens: &mut Vec<Token>) -> Result<bool, LexingError> {
-        let c = self.peek();
-        if c.is_none() {
+        let Some(c) = self.peek() else {
             return Ok(false);
-        }
-        let c = c.unwrap();
+        };
+
+        self.start_column = self.column;
+        self.start_offset = self.offset;
 
         match c {
             '(' => self.make_token(tokens, TokenType::LeftParen),
@@ -104,40 +266,118 @@ impl Scanner {
             '"' => {
                 let start_line = self.line;
                 let mut string = Vec::new();
+                // A bad escape is reported once the whole literal is consumed, so
+                // its closing quote isn't mistaken for an opening one
+                let mut escape_error = None;
 
                 self.consume();
 
-                while self.peek().is_some_and(|x| x != '"') {
-                    let s = self.consume();
+                while let Some(x) = self.peek().filter(|&x| x != '"') {
+                    let s = match x {
+                        '\\' => match self.scan_escape() {
+                            Ok(s) => s,
+                            Err(error) => {
+                                escape_error.get_or_insert(error);
+                                continue;
+                            }
+                        },
+                        _ => {
+                            self.consume();
 
-                    if s == '\n' {
-                        self.line += 1;
-                    }
+                            // Fold \r\n into a single \n
+                            if x == '\r' && self.peek() == Some('\n') {
+                                continue;
+                            }
+
+                            x
+                        }
+                    };
 
                     string.push(s);
                 }
 
                 if self.peek().is_none() {
-                    return Err(LexingError::UnterminatedString { line: start_line });
+                    return Err(LexingError::UnterminatedString {
+                        line: start_line,
+                        column: self.start_column,
+                    });
                 }
 
                 // Strip the final "
                 self.consume();
 
+                if let Some(error) = escape_error {
+                    return Err(error);
+                }
+
+                // Attribute the literal to the line it opened on
                 tokens.push(Token {
-                    line: self.line,
+                    line: start_line,
+                    column: self.start_column,
+                    start: self.start_offset,
+                    end: self.offset,
                     token_type: TokenType::String {
                         content: string.iter().collect(),
                     },
                 });
             }
 
+            // Character literal, lexed as its code point
+            '\'' => {
+                self.consume();
+
+                let c = match self.peek() {
+                    Some('\\') => self.scan_escape().map(Some),
+                    Some(x) if x != '\'' && x != '\n' => {
+                        self.consume();
+                        Ok(Some(x))
+                    }
+                    _ => Ok(None),
+                };
+
+                // Exactly one character must sit between the quotes
+                let c = match c {
+                    Ok(Some(c)) if self.peek() == Some('\'') => c,
+                    result => {
+                        // Skip the rest of the literal so it isn't rescanned as new tokens
+                        self.scan_identifer(&mut vec![]);
+
+                        if self.peek() == Some('\'') {
+                            self.consume();
+                        }
+
+                        return Err(result.err().unwrap_or(LexingError::InvalidCharLiteral {
+                            line: self.line,
+                            column: self.start_column,
+                        }));
+                    }
+                };
+
+                // Strip the final '
+                self.consume();
+
+                self.push_token(tokens, TokenType::IntLiteral { value: c as i64 });
+            }
+
             '%' => {
-                let mut string = vec![self.consume()];
+                self.consume();
+
+                let mut string = vec![c];
                 self.scan_identifer(&mut string);
 
+                if string.len() == 1 {
+                    return Err(LexingError::UnexpectedCharacter {
+                        line: self.line,
+                        column: self.start_column,
+                        character: c,
+                    });
+                }
+
                 tokens.push(Token {
                     line: self.line,
+                    column: self.start_column,
+                    start: self.start_offset,
+                    end: self.offset,
                     token_type: TokenType::Register {
                         name: string.iter().collect(),
                     },
@@ -150,85 +390,126 @@ impl Scanner {
                 let mut string = vec![];
                 self.scan_identifer(&mut string);
 
-                let function_name: String = string.iter().collect();
+                // A sigil with no name after it is reported at the sigil
+                if string.is_empty() {
+                    return Err(LexingError::UnexpectedCharacter {
+                        line: self.line,
+                        column: self.start_column,
+                        character: c,
+                    });
+                }
 
+                // Whether this names a definition site or a reference is up to the parser
                 tokens.push(Token {
                     line: self.line,
+                    column: self.start_column,
+                    start: self.start_offset,
+                    end: self.offset,
                     token_type: TokenType::Function {
-                        name: function_name.clone(),
+                        name: string.iter().collect(),
                     },
                 });
+            }
 
-                tokens.push(Token {
-                    token_type: TokenType::Label {
-                        name: function_name,
-                    },
-                    line: self.line,
-                });
-                tokens.push(Token {
-                    token_type: TokenType::Colon,
-                    line: self.line,
-                });
+            // Line comment
+            ';' => {
+                while self.peek().is_some_and(|x| x != '\n') {
+                    self.consume();
+                }
             }
 
-            ' ' | '\r' | '\t' => {
+            // Block comment
+            '/' => {
+                let start_line = self.line;
+
+                self.consume();
+
+                if self.peek() != Some('*') {
+                    return Err(LexingError::UnexpectedCharacter {
+                        line: self.line,
+                        column: self.start_column,
+                        character: c,
+                    });
+                }
+
                 self.consume();
+
+                loop {
+                    match self.peek() {
+                        None => {
+                            return Err(LexingError::UnterminatedComment {
+                                line: start_line,
+                                column: self.start_column,
+                            })
+                        }
+                        Some('*') => {
+                            self.consume();
+
+                            if self.peek() == Some('/') {
+                                self.consume();
+                                break;
+                            }
+                        }
+                        Some(_) => {
+                            self.consume();
+                        }
+                    }
+                }
             }
 
-            '\n' => {
-                self.line += 1;
+            // Negative literal, only when the sign is directly followed by a digit
+            '-' if self.peek_at(1).is_some_and(|x| x.is_ascii_digit()) => {
+                self.scan_number(tokens)?;
+            }
+
+            '-' if self.peek_at(1) == Some('>') => {
                 self.consume();
+                self.make_token(tokens, TokenType::Arrow);
             }
 
-            _ => {
-                if c.is_ascii_digit() {
-                    let mut num = Vec::new();
+            ' ' | '\r' | '\t' | '\n' => {
+                self.consume();
+            }
 
-                    while self.peek().is_some_and(|x| x.is_ascii_digit()) {
-                        num.push(self.consume());
-                    }
+            _ => {
+                // Labels may start with a digit, as long as they are `:`-terminated
+                if c.is_ascii_digit() && self.is_label_definition() {
+                    let mut word = vec![];
+                    self.scan_identifer(&mut word);
 
-                    tokens.push(Token {
-                        line: self.line,
-                        token_type: TokenType::IntLiteral {
-                            value: (num.iter().collect::<String>()).parse().unwrap(),
+                    self.push_token(
+                        tokens,
+                        TokenType::Label {
+                            name: word.into_iter().collect(),
                         },
-                    });
-                } else if c.is_ascii_alphabetic() {
-                    let mut word = vec![self.consume()];
+                    );
+                } else if c.is_ascii_digit() {
+                    self.scan_number(tokens)?;
+                } else if c.is_ascii_alphabetic() || c == '_' {
+                    self.consume();
+
+                    let mut word = vec![c];
                     self.scan_identifer(&mut word);
 
-                    match word.iter().collect::<String>().as_str() {
-                        // Types
-                        "i32" => self.make_token(tokens, TokenType::I32),
-
-                        // Keywords
-                        "add" => self.make_token(tokens, TokenType::Add),
-                        "sub" => self.make_token(tokens, TokenType::Sub),
-                        "mul" => self.make_token(tokens, TokenType::Mul),
-                        "div" => self.make_token(tokens, TokenType::Div),
-                        "exit" => self.make_token(tokens, TokenType::Exit),
-                        "define" => self.make_token(tokens, TokenType::Define),
-                        "ret" => self.make_token(tokens, TokenType::Return),
-                        "call" => self.make_token(tokens, TokenType::Call),
-                        "jmp" => self.make_token(tokens, TokenType::Jmp),
-                        "cmp" => self.make_token(tokens, TokenType::ICmp),
-                        "branch" => self.make_token(tokens, TokenType::Branch),
-
-                        // Cmp Types
-                        "le" => self.make_token(tokens, TokenType::LE),
+                    let word: String = word.into_iter().collect();
 
-                        _ => {
-                            tokens.push(Token {
-                                token_type: TokenType::Label {
-                                    name: word.into_iter().collect(),
-                                },
-                                line: self.line,
-                            });
-                        }
-                    }
+                    // A `:`-terminated identifier always names a label, even if it is a keyword
+                    let token_type = match keyword(&word) {
+                        Some(token_type) if self.peek() != Some(':') => token_type,
+                        _ => TokenType::Label { name: word },
+                    };
+
+                    self.push_token(tokens, token_type);
                 } else {
-                    return Err(LexingError::UnexpectedCharacter { line: self.line });
+                    // Consume it so that recovery always makes progress, even on
+                    // whitespace we don't skip such as a form feed
+                    self.consume();
+
+                    return Err(LexingError::UnexpectedCharacter {
+                        line: self.line,
+                        column: self.start_column,
+                        character: c,
+                    });
                 }
             }
         }
@@ -236,32 +517,836 @@ impl Scanner {
         Ok(true)
     }
 
+    fn scan_number(&mut self, tokens: &mut Vec<Token>) -> Result<(), LexingError> {
+        let start = self.index;
+        let mut num = Vec::new();
+        let mut radix = 10;
+
+        if self.peek() == Some('-') {
+            self.consume();
+            num.push('-');
+        }
+
+        let Some(first) = self.consume() else {
+            return Err(LexingError::InvalidIntLiteral {
+                line: self.line,
+                column: self.start_column,
+            });
+        };
+
+        // Radix prefixes
+        if first == '0' {
+            match self.peek() {
+                Some('x' | 'X') => radix = 16,
+                Some('b' | 'B') => radix = 2,
+                _ => {}
+            }
+        }
+
+        let digits_start = num.len();
+
+        if radix == 10 {
+            num.push(first);
+        } else {
+            self.consume();
+        }
+
+        while let Some(d) = self.peek().filter(|x| x.is_digit(radix) || *x == '_') {
+            self.consume();
+
+            // Underscores are only digit separators
+            if d != '_' {
+                num.push(d);
+            }
+        }
+
+        // A digit or letter outside the radix, as in 0b102 or 0xFFg, makes the whole
+        // literal malformed rather than splitting it into two tokens
+        if num.len() == digits_start || self.peek().is_some_and(is_identifier_char) {
+            self.scan_identifer(&mut vec![]);
+
+            return Err(LexingError::InvalidIntLiteral {
+                line: self.line,
+                column: self.start_column,
+            });
+        }
+
+        // Fractional part, only when the dot is directly followed by a digit
+        if radix == 10
+            && self.peek() == Some('.')
+            && self.peek_at(1).is_some_and(|x| x.is_ascii_digit())
+        {
+            self.consume();
+            num.push('.');
+
+            while let Some(d) = self.peek().filter(|x| x.is_ascii_digit() || *x == '_') {
+                self.consume();
+
+                if d != '_' {
+                    num.push(d);
+                }
+            }
+
+            let Ok(value) = num.iter().collect::<String>().parse() else {
+                return Err(LexingError::InvalidIntLiteral {
+                    line: self.line,
+                    column: self.start_column,
+                });
+            };
+
+            tokens.push(Token {
+                line: self.line,
+                column: self.start_column,
+                start: self.start_offset,
+                end: self.offset,
+                token_type: TokenType::FloatLiteral { value },
+            });
+
+            return Ok(());
+        }
+
+        let digits: String = num.iter().collect();
+
+        // Hex and binary literals are bit patterns, so they may use all 64 bits. A
+        // negated one is a magnitude though, which only reaches down to i64::MIN
+        let value = if radix == 10 {
+            i64::from_str_radix(&digits, radix).ok()
+        } else if let Some(magnitude) = digits.strip_prefix('-') {
+            u64::from_str_radix(magnitude, radix)
+                .ok()
+                .filter(|&x| x <= 1 << 63)
+                .map(|x| (x as i64).wrapping_neg())
+        } else {
+            u64::from_str_radix(&digits, radix).ok().map(|x| x as i64)
+        };
+
+        let Some(value) = value else {
+            return Err(LexingError::IntegerOverflow {
+                line: self.line,
+                column: self.start_column,
+                literal: self.chars[start..self.index].iter().collect(),
+            });
+        };
+
+        tokens.push(Token {
+            line: self.line,
+            column: self.start_column,
+            start: self.start_offset,
+            end: self.offset,
+            token_type: TokenType::IntLiteral { value },
+        });
+
+        Ok(())
+    }
+
+    fn scan_escape(&mut self) -> Result<char, LexingError> {
+        let column = self.column;
+
+        // Strip the \
+        self.consume();
+
+        let escaped = match self.peek() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('0') => '\0',
+            Some('x') => {
+                self.consume();
+
+                let mut value = 0;
+
+                for _ in 0..2 {
+                    let Some(digit) = self.peek().and_then(|x| x.to_digit(16)) else {
+                        return Err(LexingError::InvalidEscape {
+                            line: self.line,
+                            column,
+                        });
+                    };
+
+                    self.consume();
+                    value = value * 16 + digit;
+                }
+
+                // Content is a String, so a byte above 0x7f would silently turn into
+                // the two UTF-8 bytes of U+0080..U+00FF instead of the raw byte
+                if value > 0x7f {
+                    return Err(LexingError::InvalidEscape {
+                        line: self.line,
+                        column,
+                    });
+                }
+
+                return Ok(char::from(value as u8));
+            }
+            _ => {
+                return Err(LexingError::InvalidEscape {
+                    line: self.line,
+                    column,
+                })
+            }
+        };
+
+        self.consume();
+
+        Ok(escaped)
+    }
+
     fn make_token(&mut self, tokens: &mut Vec<Token>, token_type: TokenType) {
         self.consume();
+        self.push_token(tokens, token_type);
+    }
 
+    fn push_token(&self, tokens: &mut Vec<Token>, token_type: TokenType) {
         tokens.push(Token {
             token_type,
             line: self.line,
+            column: self.start_column,
+            start: self.start_offset,
+            end: self.offset,
         });
     }
 
     fn scan_identifer(&mut self, word: &mut Vec<char>) {
-        while self.peek().is_some_and(|x| x.is_alphanumeric()) {
-            word.push(self.consume());
+        while let Some(c) = self.peek().filter(|&x| is_identifier_char(x)) {
+            self.consume();
+            word.push(c);
+        }
+    }
+
+    // Whether the identifier starting at the current index is directly followed by a `:`
+    fn is_label_definition(&self) -> bool {
+        let mut offset = 0;
+
+        while self.peek_at(offset).is_some_and(is_identifier_char) {
+            offset += 1;
         }
+
+        self.peek_at(offset) == Some(':')
     }
 
     fn peek(&self) -> Option<char> {
-        let c = self.chars.get(self.index);
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.index + offset).copied()
+    }
+
+    // Returns `None` at the end of input, callers that need the character should
+    // use the one they peeked
+    fn consume(&mut self) -> Option<char> {
+        let c = self.peek()?;
+
+        self.index += 1;
+        self.offset += c.len_utf8();
 
+        // All line and column bookkeeping happens here, so a \r before a \n only
+        // ever counts as a column
         match c {
-            None => None,
-            Some(char) => Some(char.clone()),
+            '\n' => {
+                self.line += 1;
+                self.column = 0;
+            }
+            '\t' => self.column += TAB_WIDTH - self.column % TAB_WIDTH,
+            _ => self.column += 1,
         }
+
+        Some(c)
     }
+}
 
-    fn consume(&mut self) -> char {
-        self.index += 1;
-        self.chars.get(self.index - 1).unwrap().clone()
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Every word here is reserved. Only a `:`-terminated definition is rescued as a
+// label, a reference like `jmp add` still scans as the keyword
+fn keyword(word: &str) -> Option<TokenType> {
+    let token_type = match word {
+        // Types
+        "i32" => TokenType::I32,
+        "i64" => TokenType::I64,
+        "f64" => TokenType::F64,
+        "i1" => TokenType::I1,
+
+        // Keywords
+        "add" => TokenType::Add,
+        "sub" => TokenType::Sub,
+        "mul" => TokenType::Mul,
+        "div" => TokenType::Div,
+        "mod" => TokenType::Mod,
+        "udiv" => TokenType::UDiv,
+        "umod" => TokenType::UMod,
+        "checked" => TokenType::Checked,
+        "and" => TokenType::And,
+        "or" => TokenType::Or,
+        "xor" => TokenType::Xor,
+        "not" => TokenType::Not,
+        "shl" => TokenType::Shl,
+        "shr" => TokenType::Shr,
+        "exit" => TokenType::Exit,
+        "define" => TokenType::Define,
+        "global" => TokenType::Global,
+        "addr" => TokenType::Addr,
+        "strlen" => TokenType::StrLen,
+        "ret" => TokenType::Return,
+        "call" => TokenType::Call,
+        "jmp" => TokenType::Jmp,
+        "cmp" => TokenType::ICmp,
+        "branch" => TokenType::Branch,
+        "brcmp" => TokenType::BrCmp,
+        "load" => TokenType::Load,
+        "store" => TokenType::Store,
+        "alloca" => TokenType::Alloca,
+        "phi" => TokenType::Phi,
+        "nop" => TokenType::Nop,
+        "trap" => TokenType::Trap,
+        "zext" => TokenType::Zext,
+        "sext" => TokenType::Sext,
+        "trunc" => TokenType::Trunc,
+        "write" => TokenType::Write,
+        "select" => TokenType::Select,
+        "switch" => TokenType::Switch,
+        "to" => TokenType::To,
+
+        // Cmp Types
+        "le" => TokenType::LE,
+        "lt" => TokenType::LT,
+        "gt" => TokenType::GT,
+        "ge" => TokenType::GE,
+        "eq" => TokenType::EQ,
+        "ne" => TokenType::NE,
+        "ult" => TokenType::ULT,
+        "ugt" => TokenType::UGT,
+        "ule" => TokenType::ULE,
+        "uge" => TokenType::UGE,
+
+        _ => return None,
+    };
+
+    Some(token_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(source: &str) -> Vec<TokenType> {
+        Scanner::new(source)
+            .scan_tokens()
+            .unwrap()
+            .into_iter()
+            .map(|token| token.token_type)
+            .collect()
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            types("add ; sub\nmul"),
+            [TokenType::Add, TokenType::Mul, TokenType::EOF]
+        );
+        assert_eq!(
+            types("add /* sub\n */ mul"),
+            [TokenType::Add, TokenType::Mul, TokenType::EOF]
+        );
+
+        // A line comment may end the input, and `;` is just text in a block comment
+        assert_eq!(types("add ; c"), [TokenType::Add, TokenType::EOF]);
+        assert_eq!(types("/* ; */ add"), [TokenType::Add, TokenType::EOF]);
+
+        let tokens = Scanner::new("/*\n*/ add").scan_tokens().unwrap();
+        assert_eq!(tokens[0].line, 2);
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        let err = Scanner::new("add\n/* sub").scan_tokens().unwrap_err();
+        assert!(matches!(
+            err,
+            LexingError::UnterminatedComment { line: 2, .. }
+        ));
+    }
+
+    fn int(value: i64) -> TokenType {
+        TokenType::IntLiteral { value }
+    }
+
+    #[test]
+    fn radix_literals() {
+        assert_eq!(types("0xdeadbeef"), [int(0xdeadbeef), TokenType::EOF]);
+        assert_eq!(types("0b1010"), [int(10), TokenType::EOF]);
+        assert_eq!(types("1_000"), [int(1000), TokenType::EOF]);
+
+        for source in ["0x", "0b102", "0xFFg", "12ab"] {
+            let err = Scanner::new(source).scan_tokens().unwrap_err();
+            assert!(
+                matches!(err, LexingError::InvalidIntLiteral { .. }),
+                "{source}"
+            );
+        }
+
+        // The malformed literal is skipped as a whole when recovering
+        let (tokens, errors) = Scanner::new("0b102 add").scan_tokens_recovering();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(tokens[1].token_type, TokenType::Add);
+    }
+
+    #[test]
+    fn radix_literals_use_all_64_bits() {
+        assert_eq!(types("0xFFFFFFFFFFFFFFFF"), [int(-1), TokenType::EOF]);
+        assert_eq!(types("0x8000000000000000"), [int(i64::MIN), TokenType::EOF]);
+        assert_eq!(
+            types(&format!("0b1{}", "0".repeat(63))),
+            [int(i64::MIN), TokenType::EOF]
+        );
+
+        assert_eq!(
+            types("-0x8000000000000000"),
+            [int(i64::MIN), TokenType::EOF]
+        );
+
+        // A negated radix literal is a magnitude, so it can't wrap around
+        for source in [
+            "0x1_0000_0000_0000_0000",
+            "9223372036854775808",
+            "-0x8000000000000001",
+            "-0xFFFFFFFFFFFFFFFF",
+        ] {
+            let err = Scanner::new(source).scan_tokens().unwrap_err();
+            assert!(matches!(err, LexingError::IntegerOverflow { .. }));
+        }
+    }
+
+    #[test]
+    fn integer_overflow() {
+        let err = Scanner::new("99999999999999999999999")
+            .scan_tokens()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LexingError::IntegerOverflow { literal, .. } if literal == "99999999999999999999999"
+        ));
+    }
+
+    #[test]
+    fn negative_literals() {
+        assert_eq!(types("-5"), [int(-5), TokenType::EOF]);
+        assert_eq!(types("-0"), [int(0), TokenType::EOF]);
+        assert_eq!(
+            types("-9223372036854775808"),
+            [int(i64::MIN), TokenType::EOF]
+        );
+        assert_eq!(types("-0x10"), [int(-16), TokenType::EOF]);
+
+        // The sign must touch the digit
+        let err = Scanner::new("- 5").scan_tokens().unwrap_err();
+        assert!(matches!(
+            err,
+            LexingError::UnexpectedCharacter { character: '-', .. }
+        ));
+    }
+
+    #[test]
+    fn float_literals() {
+        let float = |value| TokenType::FloatLiteral { value };
+
+        assert_eq!(types("1.5"), [float(1.5), TokenType::EOF]);
+        assert_eq!(types("-2.25"), [float(-2.25), TokenType::EOF]);
+        assert_eq!(types("1_0.0_5"), [float(10.05), TokenType::EOF]);
+        assert_eq!(types("f64"), [TokenType::F64, TokenType::EOF]);
+
+        // A fraction needs at least one digit after the dot
+        let err = Scanner::new("1.").scan_tokens().unwrap_err();
+        assert!(matches!(
+            err,
+            LexingError::UnexpectedCharacter {
+                column: 1,
+                character: '.',
+                ..
+            }
+        ));
+    }
+
+    fn string(content: &str) -> TokenType {
+        TokenType::String {
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn multi_line_string_reports_opening_line() {
+        let tokens = Scanner::new("add\n\"a\nb\"").scan_tokens().unwrap();
+        assert_eq!(tokens[1].token_type, string("a\nb"));
+        assert_eq!(tokens[1].line, 2);
+    }
+
+    #[test]
+    fn identifiers_with_underscores() {
+        assert_eq!(
+            types("%_tmp_1 @my_func block_1"),
+            [
+                TokenType::Register {
+                    name: String::from("%_tmp_1")
+                },
+                TokenType::Function {
+                    name: String::from("my_func")
+                },
+                TokenType::Label {
+                    name: String::from("block_1")
+                },
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn first_line_is_one() {
+        let err = Scanner::new("add #\nsub").scan_tokens().unwrap_err();
+        assert!(matches!(
+            err,
+            LexingError::UnexpectedCharacter { line: 1, .. }
+        ));
+
+        let tokens = Scanner::new("add").scan_tokens().unwrap();
+        assert_eq!(tokens[0].line, 1);
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            types(r#""a\n\t\r\\\"\'\0""#),
+            [string("a\n\t\r\\\"'\0"), TokenType::EOF]
+        );
+
+        let err = Scanner::new(r#""\q""#).scan_tokens().unwrap_err();
+        assert!(matches!(err, LexingError::InvalidEscape { column: 1, .. }));
+    }
+
+    #[test]
+    fn escaped_newline_keeps_line() {
+        let tokens = Scanner::new(r#""a\nb" add"#).scan_tokens().unwrap();
+        assert_eq!(tokens[1].line, 1);
+    }
+
+    #[test]
+    fn keyword_labels() {
+        assert_eq!(
+            types("add: 1block:"),
+            [
+                TokenType::Label {
+                    name: String::from("add")
+                },
+                TokenType::Colon,
+                TokenType::Label {
+                    name: String::from("1block")
+                },
+                TokenType::Colon,
+                TokenType::EOF,
+            ]
+        );
+
+        // A keyword at the end of input must not consume past it
+        assert_eq!(types("ret"), [TokenType::Return, TokenType::EOF]);
+
+        // Only the definition is a label, a reference is still the keyword
+        assert_eq!(
+            types("jmp add"),
+            [TokenType::Jmp, TokenType::Add, TokenType::EOF]
+        );
+    }
+
+    #[test]
+    fn arrow() {
+        assert_eq!(types("-> -1"), [TokenType::Arrow, int(-1), TokenType::EOF]);
+
+        // With no digit or `>` after it, a `-` is still an unexpected character
+        let err = Scanner::new("-").scan_tokens().unwrap_err();
+        assert!(matches!(
+            err,
+            LexingError::UnexpectedCharacter { character: '-', .. }
+        ));
+    }
+
+    #[test]
+    fn unexpected_character() {
+        let err = Scanner::new("add #").scan_tokens().unwrap_err();
+        assert!(matches!(
+            err,
+            LexingError::UnexpectedCharacter {
+                line: 1,
+                column: 4,
+                character: '#'
+            }
+        ));
+    }
+
+    #[test]
+    fn recovers_from_errors() {
+        let (tokens, errors) = Scanner::new("add # sub\n$ mul").scan_tokens_recovering();
+
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            errors[0],
+            LexingError::UnexpectedCharacter { character: '#', .. }
+        ));
+        assert!(matches!(
+            errors[1],
+            LexingError::UnexpectedCharacter {
+                line: 2,
+                character: '$',
+                ..
+            }
+        ));
+
+        let types: Vec<TokenType> = tokens.into_iter().map(|token| token.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Add,
+                TokenType::Error,
+                TokenType::Sub,
+                TokenType::Error,
+                TokenType::Mul,
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    fn recover(source: &str) -> (Vec<TokenType>, Vec<LexingError>) {
+        let (tokens, errors) = Scanner::new(source).scan_tokens_recovering();
+
+        (
+            tokens.into_iter().map(|token| token.token_type).collect(),
+            errors,
+        )
+    }
+
+    #[test]
+    fn recovery_keeps_adjacent_tokens() {
+        let (types, errors) = recover("call @f(#)");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            types,
+            [
+                TokenType::Call,
+                TokenType::Function {
+                    name: String::from("f")
+                },
+                TokenType::LeftParen,
+                TokenType::Error,
+                TokenType::RightParen,
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn recovery_skips_to_the_closing_quote() {
+        let (types, errors) = recover(r#""\q b" c"#);
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            LexingError::InvalidEscape { column: 1, .. }
+        ));
+        assert_eq!(
+            types,
+            [
+                TokenType::Error,
+                TokenType::Label {
+                    name: String::from("c")
+                },
+                TokenType::EOF,
+            ]
+        );
+
+        for source in [r"'\q' add", r"'\xZZ' add", "'ab' add", "'' add"] {
+            let (types, errors) = recover(source);
+
+            assert_eq!(errors.len(), 1, "{source}");
+            assert_eq!(
+                types,
+                [TokenType::Error, TokenType::Add, TokenType::EOF],
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn crlf_line_numbers() {
+        let tokens = Scanner::new("a\r\nb").scan_tokens().unwrap();
+        assert_eq!((tokens[0].line, tokens[1].line), (1, 2));
+        assert_eq!(tokens[1].column, 0);
+
+        assert_eq!(types("\"a\r\nb\""), [string("a\nb"), TokenType::EOF]);
+    }
+
+    #[test]
+    fn tab_columns() {
+        let tokens = Scanner::new("\tadd\n  \tsub").scan_tokens().unwrap();
+        assert_eq!(tokens[0].column, 4);
+        assert_eq!(tokens[1].column, 4);
+    }
+
+    #[test]
+    fn function_is_a_single_token() {
+        assert_eq!(
+            types("call @f"),
+            [
+                TokenType::Call,
+                TokenType::Function {
+                    name: String::from("f")
+                },
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn sigil_needs_a_name() {
+        for (source, sigil) in [("@ foo", '@'), ("% foo", '%'), ("call @", '@')] {
+            let err = Scanner::new(source).scan_tokens().unwrap_err();
+            assert!(
+                matches!(err, LexingError::UnexpectedCharacter { character, .. } if character == sigil),
+                "{source}"
+            );
+        }
+
+        let (types, errors) = recover("@ foo");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            types,
+            [
+                TokenType::Error,
+                TokenType::Label {
+                    name: String::from("foo")
+                },
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn iterator_matches_scan_tokens() {
+        let source = "define @main() {\n%0 = add 1, 0x2 ; done\nret %0\n}";
+
+        let scanned: Vec<TokenType> = types(source);
+        let streamed: Vec<TokenType> = Scanner::new(source)
+            .tokens()
+            .map(|token| token.unwrap().token_type)
+            .collect();
+
+        assert_eq!(streamed, scanned);
+    }
+
+    #[test]
+    fn rescanning_starts_over() {
+        let mut scanner = Scanner::new("add # sub");
+
+        for _ in 0..2 {
+            let err = scanner.scan_tokens().unwrap_err();
+            assert!(matches!(
+                err,
+                LexingError::UnexpectedCharacter { column: 4, .. }
+            ));
+        }
+
+        let (tokens, _) = scanner.scan_tokens_recovering();
+        assert_eq!((tokens[2].line, tokens[2].column), (1, 6));
+        assert_eq!((tokens[2].start, tokens[2].end), (6, 9));
+
+        let mut scanner = Scanner::new("add\nsub");
+        scanner.tokens().next();
+
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Add);
+        assert_eq!((tokens[1].line, tokens[1].start), (2, 4));
+    }
+
+    #[test]
+    fn spans_slice_token_text() {
+        let source = "%x = add \"ü\", -12 ; c\n@f";
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+
+        let texts: Vec<&str> = tokens
+            .iter()
+            .map(|token| &source[token.start..token.end])
+            .collect();
+
+        assert_eq!(texts, ["%x", "=", "add", "\"ü\"", ",", "-12", "@f", ""]);
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(
+            types(r"'A' '\n' '\''"),
+            [int(65), int(10), int(39), TokenType::EOF]
+        );
+
+        for source in ["''", "'ab'", "'a"] {
+            let err = Scanner::new(source).scan_tokens().unwrap_err();
+            assert!(matches!(err, LexingError::InvalidCharLiteral { .. }));
+        }
+    }
+
+    #[test]
+    fn hex_escapes() {
+        assert_eq!(types(r#""\x41""#), [string("A"), TokenType::EOF]);
+        assert_eq!(types(r#""\x7f""#), [string("\x7f"), TokenType::EOF]);
+
+        // Strings hold UTF-8 text, so a raw high byte can't be represented yet
+        for source in [
+            r#""\x""#,
+            r#""\xG""#,
+            r#""\xZZ""#,
+            r#""\x4""#,
+            r#""\x80""#,
+            r#""\xFF""#,
+        ] {
+            let err = Scanner::new(source).scan_tokens().unwrap_err();
+            assert!(matches!(err, LexingError::InvalidEscape { column: 1, .. }));
+        }
+    }
+
+    #[test]
+    fn random_input_never_panics() {
+        let alphabet = b"%@'\"\\/*;:-0xb19_.aZ>{}(),= \n\r\t\x0c";
+
+        // xorshift, so a failing input reproduces on every run
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..10_000 {
+            let len = next() % 32;
+            let data: Vec<u8> = (0..len)
+                .map(|_| match next() {
+                    // Mostly scanner syntax, with some arbitrary bytes and invalid UTF-8
+                    x if x % 4 == 0 => (x >> 32) as u8,
+                    x => alphabet[(x >> 32) as usize % alphabet.len()],
+                })
+                .collect();
+
+            let _ = scan_bytes(&data);
+
+            let source = String::from_utf8_lossy(&data);
+            let (tokens, _) = Scanner::new(&source).scan_tokens_recovering();
+
+            assert_eq!(tokens.last().unwrap().token_type, TokenType::EOF);
+            for token in &tokens {
+                assert!(source.get(token.start..token.end).is_some(), "{source:?}");
+            }
+
+            Scanner::new(&source).tokens().for_each(drop);
+        }
     }
 }